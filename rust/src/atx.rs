@@ -84,7 +84,7 @@
 //! ```
 
 use crate::{error::io_enum_to_result, ops, IoError, IoResult, Ops};
-use avb_bindgen::avb_atx_validate_vbmeta_public_key;
+use avb_bindgen::{avb_atx_validate_unlock_credential, avb_atx_validate_vbmeta_public_key};
 
 /// ATX permanent attributes.
 pub use avb_bindgen::AvbAtxPermanentAttributes as AtxPermanentAttributes;
@@ -108,6 +108,12 @@ pub const ATX_PIK_VERSION_LOCATION: usize = avb_bindgen::AVB_ATX_PIK_VERSION_LOC
 /// If using ATX APIs, make sure no vbmetas use this location, it must be reserved for the PSK.
 pub const ATX_PSK_VERSION_LOCATION: usize = avb_bindgen::AVB_ATX_PSK_VERSION_LOCATION as usize;
 
+/// Size in bytes of the random nonce embedded in an unlock challenge.
+pub const ATX_UNLOCK_CHALLENGE_SIZE: usize = avb_bindgen::AVB_ATX_UNLOCK_CHALLENGE_SIZE as usize;
+
+/// ATX schema version, used to populate the `version` field of generated ATX structures.
+const ATX_SCHEMA_VERSION: u32 = avb_bindgen::AVB_ATX_SCHEMA_VERSION;
+
 /// ATX callbacks.
 pub trait AtxOps {
     /// Reads the device's permanent attributes.
@@ -141,6 +147,43 @@ pub trait AtxOps {
     /// The 32-byte SHA256 digest on success, error on failure.
     fn read_permanent_attributes_hash(&mut self) -> IoResult<[u8; SHA256_DIGEST_SIZE]>;
 
+    /// Writes the device's permanent attributes during factory provisioning.
+    ///
+    /// This is only ever expected to be called once per device, from
+    /// `atx_provision_permanent_attributes()` during manufacturing; devices that don't support
+    /// provisioning through this crate can leave it returning `IoError::NotImplemented`.
+    ///
+    /// # Arguments
+    /// * `attributes`: permanent attributes to write.
+    ///
+    /// # Returns
+    /// Unit on success, error on failure.
+    fn write_permanent_attributes(
+        &mut self,
+        #[allow(unused_variables)] attributes: &AtxPermanentAttributes,
+    ) -> IoResult<()> {
+        Err(IoError::NotImplemented)
+    }
+
+    /// Writes the SHA256 hash of the device's permanent attributes to the secure storage hash
+    /// slot read by `read_permanent_attributes_hash()`.
+    ///
+    /// This is only ever expected to be called once per device, from
+    /// `atx_provision_permanent_attributes()` during manufacturing; devices that don't support
+    /// provisioning through this crate can leave it returning `IoError::NotImplemented`.
+    ///
+    /// # Arguments
+    /// * `hash`: the 32-byte SHA256 digest to commit.
+    ///
+    /// # Returns
+    /// Unit on success, error on failure.
+    fn write_permanent_attributes_hash(
+        &mut self,
+        #[allow(unused_variables)] hash: &[u8; SHA256_DIGEST_SIZE],
+    ) -> IoResult<()> {
+        Err(IoError::NotImplemented)
+    }
+
     /// Provides the key version for the rotateable keys.
     ///
     /// ATX stores signing key versions as rollback indices; when this function is called it
@@ -175,6 +218,69 @@ pub trait AtxOps {
     /// # Returns
     /// Unit on success, error on failure.
     fn get_random(&mut self, bytes: &mut [u8]) -> IoResult<()>;
+
+    /// Persists the nonce from the most recently generated unlock challenge.
+    ///
+    /// This is only used for authenticated unlock. If authenticated unlock is not needed, this
+    /// can be left returning `IoError::NotImplemented`.
+    ///
+    /// The stored nonce must survive until the next `atx_validate_unlock_credential()` call
+    /// (including across reboots) so that a credential can only be used to answer the challenge
+    /// it was issued for. Generating a new challenge overwrites any previously-stored nonce.
+    ///
+    /// # Arguments
+    /// * `nonce`: the challenge nonce to persist.
+    ///
+    /// # Returns
+    /// Unit on success, error on failure.
+    fn set_stored_challenge_nonce(
+        &mut self,
+        #[allow(unused_variables)] nonce: &[u8; ATX_UNLOCK_CHALLENGE_SIZE],
+    ) -> IoResult<()> {
+        Err(IoError::NotImplemented)
+    }
+
+    /// Reads the previously-stored unlock challenge nonce, without invalidating it.
+    ///
+    /// This is only used for authenticated unlock. If authenticated unlock is not needed, this
+    /// can be left returning `IoError::NotImplemented`.
+    ///
+    /// # Returns
+    /// The previously-stored nonce, or `IoError::Io` if none has been stored.
+    fn peek_stored_challenge_nonce(&mut self) -> IoResult<[u8; ATX_UNLOCK_CHALLENGE_SIZE]> {
+        Err(IoError::NotImplemented)
+    }
+
+    /// Invalidates the previously-stored unlock challenge nonce.
+    ///
+    /// This is only used for authenticated unlock. If authenticated unlock is not needed, this
+    /// can be left returning `IoError::NotImplemented`.
+    ///
+    /// Implementations must ensure that once this has been called, `peek_stored_challenge_nonce()`
+    /// can no longer return the cleared nonce, so that a given challenge can only ever be redeemed
+    /// by a single credential.
+    ///
+    /// # Returns
+    /// Unit on success, error on failure.
+    fn clear_stored_challenge_nonce(&mut self) -> IoResult<()> {
+        Err(IoError::NotImplemented)
+    }
+}
+
+/// Computes the SHA256 digest of `data` using the underlying libavb implementation.
+fn sha256(data: &[u8]) -> [u8; SHA256_DIGEST_SIZE] {
+    // SAFETY:
+    // * `ctx` is a plain-old-data struct, fully initialized by `avb_sha256_init()` below before
+    //   any other function touches it.
+    // * `data` is a valid byte buffer for the duration of the call.
+    // * `avb_sha256_final()` returns a pointer into `ctx`, which we copy out of before `ctx` is
+    //   dropped.
+    unsafe {
+        let mut ctx = core::mem::zeroed::<avb_bindgen::AvbSHA256Ctx>();
+        avb_bindgen::avb_sha256_init(&mut ctx);
+        avb_bindgen::avb_sha256_update(&mut ctx, data.as_ptr(), data.len());
+        *(avb_bindgen::avb_sha256_final(&mut ctx) as *const [u8; SHA256_DIGEST_SIZE])
+    }
 }
 
 /// ATX-provided vbmeta key validation.
@@ -253,9 +359,25 @@ pub fn atx_validate_vbmeta_public_key(
 ///
 /// # Returns
 /// The challenge to sign with the PUK, or `IoError` on `atx_ops` failure.
-pub fn atx_generate_unlock_challenge(_atx_ops: &mut dyn AtxOps) -> IoResult<AtxUnlockChallenge> {
-    // TODO(b/320543206): implement
-    Err(IoError::NotImplemented)
+pub fn atx_generate_unlock_challenge(atx_ops: &mut dyn AtxOps) -> IoResult<AtxUnlockChallenge> {
+    // SAFETY: `AvbAtxPermanentAttributes` is a plain-old-data struct; it's fully initialized by
+    // `read_permanent_attributes()` below before we read any of its fields.
+    let mut attributes = unsafe { core::mem::zeroed::<AtxPermanentAttributes>() };
+    atx_ops.read_permanent_attributes(&mut attributes)?;
+    let product_id_hash = sha256(&attributes.product_id);
+
+    let mut nonce = [0u8; ATX_UNLOCK_CHALLENGE_SIZE];
+    atx_ops.get_random(&mut nonce)?;
+    // Persist the nonce now, before returning the challenge, so a later
+    // `atx_validate_unlock_credential()` call can check the credential was actually signed in
+    // response to this specific challenge rather than a replayed one.
+    atx_ops.set_stored_challenge_nonce(&nonce)?;
+
+    Ok(AtxUnlockChallenge {
+        version: ATX_SCHEMA_VERSION,
+        product_id_hash,
+        challenge: nonce,
+    })
 }
 
 /// Validates a signed credential for authenticated unlock.
@@ -263,6 +385,10 @@ pub fn atx_generate_unlock_challenge(_atx_ops: &mut dyn AtxOps) -> IoResult<AtxU
 /// Used to check that an unlock credential was properly signed with the PUK according to the
 /// device's permanent attributes.
 ///
+/// The stored challenge nonce is only invalidated once this function reaches a definitive
+/// `Ok(true)`/`Ok(false)` result; a transient `Err(IoError)` (e.g. a storage read failure) leaves
+/// the nonce in place so the same challenge can be retried.
+///
 /// # Arguments
 /// * `ops`: the `Ops` callback implementations, which must provide an `atx_ops()` implementation.
 /// * `credential`: the signed unlock credential to verify.
@@ -272,9 +398,590 @@ pub fn atx_generate_unlock_challenge(_atx_ops: &mut dyn AtxOps) -> IoResult<AtxU
 /// * `Ok(false)` if it failed validation
 /// * `Err(IoError)` on `ops` failure
 pub fn atx_validate_unlock_credential(
-    _ops: &mut dyn Ops,
-    _credential: &AtxUnlockCredential,
+    ops: &mut dyn Ops,
+    credential: &AtxUnlockCredential,
 ) -> IoResult<bool> {
-    // TODO(b/320543206): implement
-    Err(IoError::NotImplemented)
+    // This API requires both AVB and ATX ops.
+    if ops.atx_ops().is_none() {
+        return Err(IoError::NotImplemented);
+    }
+
+    let challenge = reconstruct_unlock_challenge(ops.atx_ops().unwrap())?;
+
+    let mut user_data = ops::UserData::new(ops);
+    let mut scoped_ops = ops::ScopedAvbOps::new(&mut user_data);
+
+    let mut trusted: bool = false;
+    let result = io_enum_to_result(
+        // SAFETY:
+        // * `scoped_ops.as_mut()` gives us a valid C `AvbOps` with ATX support.
+        // * `challenge` and `credential` point to valid, fully-initialized structures.
+        // * `trusted` is a C-compatible bool.
+        // * this function does not retain references to any of these arguments.
+        unsafe {
+            avb_atx_validate_unlock_credential(
+                scoped_ops.as_mut(),
+                &challenge,
+                credential,
+                &mut trusted,
+            )
+        },
+    )
+    .map(|()| trusted);
+
+    // The PIK/PUK certificate chain, key version rollback checks, and the signature over
+    // `challenge` are all verified by `avb_atx_validate_unlock_credential()` above; a mismatch
+    // anywhere in that chain surfaces here as `trusted == false` rather than an error.
+    finish_unlock_validation(ops.atx_ops().unwrap(), result)
+}
+
+/// Reconstructs the challenge an unlock credential should be answering from the currently stored
+/// nonce and the device's permanent attributes, without invalidating the nonce.
+fn reconstruct_unlock_challenge(atx_ops: &mut dyn AtxOps) -> IoResult<AtxUnlockChallenge> {
+    let nonce = atx_ops.peek_stored_challenge_nonce()?;
+    // SAFETY: `AvbAtxPermanentAttributes` is a plain-old-data struct; it's fully initialized by
+    // `read_permanent_attributes()` below before we read any of its fields.
+    let mut attributes = unsafe { core::mem::zeroed::<AtxPermanentAttributes>() };
+    atx_ops.read_permanent_attributes(&mut attributes)?;
+    Ok(AtxUnlockChallenge {
+        version: ATX_SCHEMA_VERSION,
+        product_id_hash: sha256(&attributes.product_id),
+        challenge: nonce,
+    })
+}
+
+/// Applies the nonce-invalidation policy to a completed unlock credential verification.
+///
+/// The stored challenge nonce is only consumed once `result` is a definitive `Ok(true)` or
+/// `Ok(false)`; a transient `Err(IoError)` leaves the nonce in place so the same challenge can be
+/// retried.
+fn finish_unlock_validation(atx_ops: &mut dyn AtxOps, result: IoResult<bool>) -> IoResult<bool> {
+    let trusted = result?;
+    atx_ops.clear_stored_challenge_nonce()?;
+    Ok(trusted)
+}
+
+/// Returns whether `public_key` is a well-formed AVB RSA public key blob: an 8-byte
+/// `(key_num_bits, n0inv)` header followed by a modulus and an `rr` value, each
+/// `key_num_bits / 8` bytes, with no trailing or missing bytes.
+///
+/// This is a structural check only; it does not validate the key is otherwise usable.
+fn is_well_formed_avb_public_key(public_key: &[u8]) -> bool {
+    let Some(header) = public_key.get(0..4) else {
+        return false;
+    };
+    let key_num_bits = u32::from_be_bytes(header.try_into().unwrap());
+    let key_num_bytes = (key_num_bits / 8) as usize;
+    key_num_bytes > 0 && public_key.len() == 8 + 2 * key_num_bytes
+}
+
+/// Provisions (fuses) the device's permanent attributes.
+///
+/// This is intended to be called once, during factory provisioning, typically in response to
+/// `fastboot oem fuse at-perm-attr`. It validates the basic structure of `attributes` before
+/// writing it via `AtxOps::write_permanent_attributes()`, and commits the resulting SHA256 hash
+/// via `AtxOps::write_permanent_attributes_hash()` so `AtxOps::read_permanent_attributes_hash()`
+/// reflects it afterwards.
+///
+/// To match real one-time-programmable secure storage, this refuses to run if the device already
+/// has a non-zero permanent attributes hash, unless `force` is set. The hash is committed before
+/// the attributes themselves are written, so that if `write_permanent_attributes()` fails partway
+/// through, the write-once guard still trips on retry instead of treating the device as
+/// unprovisioned; a caller hitting that failure must pass `force` to retry.
+///
+/// # Arguments
+/// * `atx_ops`: the `AtxOps` callback implementations.
+/// * `attributes`: the permanent attributes to provision.
+/// * `force`: if true, provisions even if the device already has permanent attributes.
+///
+/// # Returns
+/// Unit on success, `IoError` if `attributes` is malformed, the device is already provisioned and
+/// `force` is false, or on `atx_ops` failure.
+pub fn atx_provision_permanent_attributes(
+    atx_ops: &mut dyn AtxOps,
+    attributes: &AtxPermanentAttributes,
+    force: bool,
+) -> IoResult<()> {
+    if attributes.version != ATX_SCHEMA_VERSION {
+        return Err(IoError::Io);
+    }
+    if !is_well_formed_avb_public_key(&attributes.product_root_public_key) {
+        return Err(IoError::Io);
+    }
+
+    if !force {
+        let current_hash = atx_ops.read_permanent_attributes_hash()?;
+        if current_hash != [0u8; SHA256_DIGEST_SIZE] {
+            return Err(IoError::Io);
+        }
+    }
+
+    // SAFETY: `AvbAtxPermanentAttributes` is `#[repr(C, packed)]` plain-old-data with no padding
+    // bytes, so reinterpreting it as a byte slice of its exact size gives exactly the same bytes
+    // `read_permanent_attributes_hash()` is expected to have hashed, and the slice does not
+    // outlive `attributes`.
+    let attributes_bytes = unsafe {
+        core::slice::from_raw_parts(
+            (attributes as *const AtxPermanentAttributes) as *const u8,
+            core::mem::size_of::<AtxPermanentAttributes>(),
+        )
+    };
+    atx_ops.write_permanent_attributes_hash(&sha256(attributes_bytes))?;
+    atx_ops.write_permanent_attributes(attributes)
+}
+
+/// Maximum number of rollback locations a single `KeyVersionCache` can hold.
+///
+/// `AtxOps::set_key_version()` is only ever called for the PIK and PSK locations, so two slots
+/// are sufficient.
+const MAX_CACHED_KEY_VERSIONS: usize = 2;
+
+/// Returns whether `rollback_index_location` is reserved for ATX key version tracking.
+///
+/// Integrators must ensure no vbmeta image uses `ATX_PIK_VERSION_LOCATION` or
+/// `ATX_PSK_VERSION_LOCATION` as a regular rollback index location; this can be used wherever
+/// vbmeta rollback locations are configured to assert that invariant.
+pub fn is_atx_reserved_version_location(rollback_index_location: usize) -> bool {
+    matches!(
+        rollback_index_location,
+        ATX_PIK_VERSION_LOCATION | ATX_PSK_VERSION_LOCATION
+    )
+}
+
+/// Cache of PIK/PSK key versions reported during verification, pending commit to rollback
+/// storage.
+///
+/// `AtxOps::set_key_version()` intentionally only caches reported versions rather than writing
+/// them to rollback storage immediately, since doing so too early can break A/B fallback after an
+/// OTA (see its docs). Callers should accumulate versions into a `KeyVersionCache` from
+/// `set_key_version()` during verification, then call `commit()` once the running slot is known
+/// to be good, e.g. from the "mark slot successful" path rather than at verify time.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct KeyVersionCache {
+    versions: [Option<(usize, u64)>; MAX_CACHED_KEY_VERSIONS],
+}
+
+impl KeyVersionCache {
+    /// Creates an empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that `rollback_index_location` reported `key_version`.
+    ///
+    /// If the location has already been recorded, the cached value is overwritten.
+    ///
+    /// Callers are only ever expected to report the reserved ATX PIK/PSK locations, since those
+    /// are the only ones `AtxOps::set_key_version()` is invoked for, but this does not assert
+    /// that here: with `MAX_CACHED_KEY_VERSIONS` sized exactly to the number of reserved
+    /// locations, doing so would make the overflow case below unreachable to exercise, and
+    /// returning `IoError::Oom` is itself sufficient to keep a misbehaving caller from silently
+    /// losing a cached version.
+    ///
+    /// # Returns
+    /// Unit on success, `IoError::Oom` if `rollback_index_location` is a new, distinct location
+    /// and the cache is already holding `MAX_CACHED_KEY_VERSIONS` other locations.
+    pub fn set(&mut self, rollback_index_location: usize, key_version: u64) -> IoResult<()> {
+        if let Some(slot) = self
+            .versions
+            .iter_mut()
+            .flatten()
+            .find(|(location, _)| *location == rollback_index_location)
+        {
+            slot.1 = key_version;
+            return Ok(());
+        }
+        match self.versions.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some((rollback_index_location, key_version));
+                Ok(())
+            }
+            None => Err(IoError::Oom),
+        }
+    }
+
+    /// Returns the cached `(rollback_index_location, key_version)` pairs.
+    pub fn versions(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.versions.into_iter().flatten()
+    }
+
+    /// Writes all cached key versions to rollback storage via `ops`.
+    ///
+    /// Each location is only ever moved forward: if the value currently stored at a location is
+    /// already greater than or equal to the cached version, it's left untouched.
+    ///
+    /// # Returns
+    /// Unit on success, error on failure.
+    pub fn commit(&self, ops: &mut dyn Ops) -> IoResult<()> {
+        for (location, version) in self.versions() {
+            let current = ops.read_rollback_index(location)?;
+            if version > current {
+                ops.write_rollback_index(location, version)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::ffi::CStr;
+
+    /// Minimal `AtxOps` fake covering only what the authenticated-unlock and provisioning tests
+    /// need; failure injection flags let tests exercise the error paths.
+    struct FakeAtxOps {
+        attributes: AtxPermanentAttributes,
+        fail_read_permanent_attributes: bool,
+        stored_nonce: Option<[u8; ATX_UNLOCK_CHALLENGE_SIZE]>,
+        attributes_hash: [u8; SHA256_DIGEST_SIZE],
+        write_order: Vec<&'static str>,
+        written_attributes: Option<AtxPermanentAttributes>,
+        written_hash: Option<[u8; SHA256_DIGEST_SIZE]>,
+        fail_write_attributes: bool,
+        fail_write_hash: bool,
+    }
+
+    impl FakeAtxOps {
+        fn new() -> Self {
+            // SAFETY: `AvbAtxPermanentAttributes` is a plain-old-data struct; an all-zero value
+            // is a valid (if meaningless) instance of it.
+            let attributes = unsafe { core::mem::zeroed::<AtxPermanentAttributes>() };
+            Self {
+                attributes,
+                fail_read_permanent_attributes: false,
+                stored_nonce: None,
+                attributes_hash: [0u8; SHA256_DIGEST_SIZE],
+                write_order: Vec::new(),
+                written_attributes: None,
+                written_hash: None,
+                fail_write_attributes: false,
+                fail_write_hash: false,
+            }
+        }
+    }
+
+    impl AtxOps for FakeAtxOps {
+        fn read_permanent_attributes(
+            &mut self,
+            attributes: &mut AtxPermanentAttributes,
+        ) -> IoResult<()> {
+            if self.fail_read_permanent_attributes {
+                return Err(IoError::Io);
+            }
+            *attributes = self.attributes;
+            Ok(())
+        }
+
+        fn read_permanent_attributes_hash(&mut self) -> IoResult<[u8; SHA256_DIGEST_SIZE]> {
+            Ok(self.attributes_hash)
+        }
+
+        fn write_permanent_attributes(&mut self, attributes: &AtxPermanentAttributes) -> IoResult<()> {
+            if self.fail_write_attributes {
+                return Err(IoError::Io);
+            }
+            self.write_order.push("attributes");
+            self.written_attributes = Some(*attributes);
+            Ok(())
+        }
+
+        fn write_permanent_attributes_hash(
+            &mut self,
+            hash: &[u8; SHA256_DIGEST_SIZE],
+        ) -> IoResult<()> {
+            if self.fail_write_hash {
+                return Err(IoError::Io);
+            }
+            self.write_order.push("hash");
+            self.written_hash = Some(*hash);
+            // Mirror real secure storage: once committed, a later `read_permanent_attributes_hash()`
+            // sees this value.
+            self.attributes_hash = *hash;
+            Ok(())
+        }
+
+        fn set_key_version(&mut self, _rollback_index_location: usize, _key_version: u64) {}
+
+        fn get_random(&mut self, _bytes: &mut [u8]) -> IoResult<()> {
+            Err(IoError::NotImplemented)
+        }
+
+        fn set_stored_challenge_nonce(
+            &mut self,
+            nonce: &[u8; ATX_UNLOCK_CHALLENGE_SIZE],
+        ) -> IoResult<()> {
+            self.stored_nonce = Some(*nonce);
+            Ok(())
+        }
+
+        fn peek_stored_challenge_nonce(&mut self) -> IoResult<[u8; ATX_UNLOCK_CHALLENGE_SIZE]> {
+            self.stored_nonce.ok_or(IoError::Io)
+        }
+
+        fn clear_stored_challenge_nonce(&mut self) -> IoResult<()> {
+            self.stored_nonce = None;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn reconstruct_unlock_challenge_builds_challenge_from_stored_nonce() {
+        let mut atx_ops = FakeAtxOps::new();
+        let nonce = [7u8; ATX_UNLOCK_CHALLENGE_SIZE];
+        atx_ops.set_stored_challenge_nonce(&nonce).unwrap();
+
+        let challenge = reconstruct_unlock_challenge(&mut atx_ops).unwrap();
+
+        assert_eq!(challenge.challenge, nonce);
+        // The nonce is only peeked, never consumed, by reconstruction alone.
+        assert_eq!(atx_ops.stored_nonce, Some(nonce));
+    }
+
+    #[test]
+    fn reconstruct_unlock_challenge_propagates_attribute_read_error_without_consuming_nonce() {
+        let mut atx_ops = FakeAtxOps::new();
+        let nonce = [9u8; ATX_UNLOCK_CHALLENGE_SIZE];
+        atx_ops.set_stored_challenge_nonce(&nonce).unwrap();
+        atx_ops.fail_read_permanent_attributes = true;
+
+        let result = reconstruct_unlock_challenge(&mut atx_ops);
+
+        assert!(matches!(result, Err(IoError::Io)));
+        assert_eq!(atx_ops.stored_nonce, Some(nonce));
+    }
+
+    #[test]
+    fn finish_unlock_validation_clears_nonce_on_definitive_true() {
+        let mut atx_ops = FakeAtxOps::new();
+        atx_ops
+            .set_stored_challenge_nonce(&[1u8; ATX_UNLOCK_CHALLENGE_SIZE])
+            .unwrap();
+
+        let trusted = finish_unlock_validation(&mut atx_ops, Ok(true)).unwrap();
+
+        assert!(trusted);
+        assert_eq!(atx_ops.stored_nonce, None);
+    }
+
+    #[test]
+    fn finish_unlock_validation_clears_nonce_on_definitive_false() {
+        // A credential signed over a stale or different nonce fails the signature check inside
+        // `avb_atx_validate_unlock_credential()`, which surfaces here as a definitive `Ok(false)`
+        // rather than an error.
+        let mut atx_ops = FakeAtxOps::new();
+        atx_ops
+            .set_stored_challenge_nonce(&[2u8; ATX_UNLOCK_CHALLENGE_SIZE])
+            .unwrap();
+
+        let trusted = finish_unlock_validation(&mut atx_ops, Ok(false)).unwrap();
+
+        assert!(!trusted);
+        assert_eq!(atx_ops.stored_nonce, None);
+    }
+
+    #[test]
+    fn finish_unlock_validation_preserves_nonce_on_transient_error() {
+        let mut atx_ops = FakeAtxOps::new();
+        let nonce = [3u8; ATX_UNLOCK_CHALLENGE_SIZE];
+        atx_ops.set_stored_challenge_nonce(&nonce).unwrap();
+
+        let result = finish_unlock_validation(&mut atx_ops, Err(IoError::Io));
+
+        assert!(matches!(result, Err(IoError::Io)));
+        assert_eq!(atx_ops.stored_nonce, Some(nonce));
+    }
+
+    /// Builds permanent attributes that pass both the version and PRK structural checks in
+    /// `atx_provision_permanent_attributes()`, regardless of the real size of
+    /// `product_root_public_key` on this build.
+    fn valid_test_attributes() -> AtxPermanentAttributes {
+        // SAFETY: `AvbAtxPermanentAttributes` is a plain-old-data struct; an all-zero value is a
+        // valid starting point before we fill in the fields the validation checks.
+        let mut attributes = unsafe { core::mem::zeroed::<AtxPermanentAttributes>() };
+        attributes.version = ATX_SCHEMA_VERSION;
+        let key_num_bytes = (attributes.product_root_public_key.len() - 8) / 2;
+        let key_num_bits = (key_num_bytes * 8) as u32;
+        attributes.product_root_public_key[0..4].copy_from_slice(&key_num_bits.to_be_bytes());
+        attributes
+    }
+
+    #[test]
+    fn provision_permanent_attributes_rejects_already_provisioned_device() {
+        let mut atx_ops = FakeAtxOps::new();
+        atx_ops.attributes_hash = [0xAA; SHA256_DIGEST_SIZE];
+
+        let result =
+            atx_provision_permanent_attributes(&mut atx_ops, &valid_test_attributes(), false);
+
+        assert!(matches!(result, Err(IoError::Io)));
+        assert!(atx_ops.written_attributes.is_none());
+        assert!(atx_ops.written_hash.is_none());
+    }
+
+    #[test]
+    fn provision_permanent_attributes_force_overrides_already_provisioned_guard() {
+        let mut atx_ops = FakeAtxOps::new();
+        atx_ops.attributes_hash = [0xAA; SHA256_DIGEST_SIZE];
+
+        let attributes = valid_test_attributes();
+        atx_provision_permanent_attributes(&mut atx_ops, &attributes, true).unwrap();
+
+        assert!(atx_ops.written_attributes.is_some());
+        assert!(atx_ops.written_hash.is_some());
+    }
+
+    #[test]
+    fn provision_permanent_attributes_commits_hash_before_attributes() {
+        let mut atx_ops = FakeAtxOps::new();
+
+        let attributes = valid_test_attributes();
+        atx_provision_permanent_attributes(&mut atx_ops, &attributes, false).unwrap();
+
+        assert_eq!(atx_ops.write_order, vec!["hash", "attributes"]);
+    }
+
+    #[test]
+    fn provision_permanent_attributes_leaves_write_once_guard_tripped_if_attributes_write_fails() {
+        let mut atx_ops = FakeAtxOps::new();
+        atx_ops.fail_write_attributes = true;
+
+        let attributes = valid_test_attributes();
+        let result = atx_provision_permanent_attributes(&mut atx_ops, &attributes, false);
+
+        assert!(result.is_err());
+        // The hash was committed before the (failing) attributes write, so a retry without
+        // `force` is correctly refused rather than silently allowed.
+        assert!(atx_ops.written_hash.is_some());
+        let retry = atx_provision_permanent_attributes(&mut atx_ops, &attributes, false);
+        assert!(matches!(retry, Err(IoError::Io)));
+    }
+
+    /// Minimal `Ops` fake covering only what `KeyVersionCache::commit()` needs; every other
+    /// method is unreachable from these tests and panics if called.
+    struct FakeOps {
+        rollback_indices: std::collections::HashMap<usize, u64>,
+    }
+
+    impl FakeOps {
+        fn new() -> Self {
+            Self {
+                rollback_indices: std::collections::HashMap::new(),
+            }
+        }
+
+        fn with_rollback_index(mut self, location: usize, index: u64) -> Self {
+            self.rollback_indices.insert(location, index);
+            self
+        }
+    }
+
+    impl Ops for FakeOps {
+        fn read_from_partition(
+            &mut self,
+            _partition: &CStr,
+            _offset: i64,
+            _buffer: &mut [u8],
+        ) -> IoResult<usize> {
+            unimplemented!("not exercised by these tests")
+        }
+
+        fn validate_vbmeta_public_key(
+            &mut self,
+            _public_key: &[u8],
+            _public_key_metadata: Option<&[u8]>,
+        ) -> IoResult<bool> {
+            unimplemented!("not exercised by these tests")
+        }
+
+        fn read_rollback_index(&mut self, rollback_index_location: usize) -> IoResult<u64> {
+            Ok(*self
+                .rollback_indices
+                .get(&rollback_index_location)
+                .unwrap_or(&0))
+        }
+
+        fn write_rollback_index(
+            &mut self,
+            rollback_index_location: usize,
+            index: u64,
+        ) -> IoResult<()> {
+            self.rollback_indices.insert(rollback_index_location, index);
+            Ok(())
+        }
+
+        fn read_is_device_unlocked(&mut self) -> IoResult<bool> {
+            unimplemented!("not exercised by these tests")
+        }
+
+        fn get_unique_guid_for_partition<'a>(
+            &mut self,
+            _partition: &CStr,
+            _buffer: &'a mut [u8],
+        ) -> IoResult<&'a CStr> {
+            unimplemented!("not exercised by these tests")
+        }
+
+        fn get_size_of_partition(&mut self, _partition: &CStr) -> IoResult<u64> {
+            unimplemented!("not exercised by these tests")
+        }
+
+        fn read_persistent_value(&mut self, _name: &CStr, _value: &mut [u8]) -> IoResult<usize> {
+            unimplemented!("not exercised by these tests")
+        }
+
+        fn write_persistent_value(&mut self, _name: &CStr, _value: &[u8]) -> IoResult<()> {
+            unimplemented!("not exercised by these tests")
+        }
+
+        fn erase_persistent_value(&mut self, _name: &CStr) -> IoResult<()> {
+            unimplemented!("not exercised by these tests")
+        }
+    }
+
+    #[test]
+    fn key_version_cache_set_overwrites_existing_location_in_place() {
+        let mut cache = KeyVersionCache::new();
+        cache.set(ATX_PIK_VERSION_LOCATION, 1).unwrap();
+        cache.set(ATX_PIK_VERSION_LOCATION, 2).unwrap();
+
+        let versions: Vec<_> = cache.versions().collect();
+        assert_eq!(versions, vec![(ATX_PIK_VERSION_LOCATION, 2)]);
+    }
+
+    #[test]
+    fn key_version_cache_set_surfaces_overflow_instead_of_dropping_it() {
+        let mut cache = KeyVersionCache::new();
+        cache.set(ATX_PIK_VERSION_LOCATION, 1).unwrap();
+        cache.set(ATX_PSK_VERSION_LOCATION, 1).unwrap();
+
+        // Location 0 is a regular vbmeta rollback location, distinct from both cached ones.
+        let result = cache.set(0, 1);
+
+        assert!(matches!(result, Err(IoError::Oom)));
+        // The existing entries are untouched by the rejected insert.
+        assert_eq!(cache.versions().count(), 2);
+    }
+
+    #[test]
+    fn key_version_cache_commit_advances_stale_stored_index() {
+        let mut ops = FakeOps::new().with_rollback_index(ATX_PIK_VERSION_LOCATION, 2);
+        let mut cache = KeyVersionCache::new();
+        cache.set(ATX_PIK_VERSION_LOCATION, 7).unwrap();
+
+        cache.commit(&mut ops).unwrap();
+
+        assert_eq!(ops.rollback_indices[&ATX_PIK_VERSION_LOCATION], 7);
+    }
+
+    #[test]
+    fn key_version_cache_commit_never_lowers_an_already_stored_index() {
+        let mut ops = FakeOps::new().with_rollback_index(ATX_PIK_VERSION_LOCATION, 5);
+        let mut cache = KeyVersionCache::new();
+        cache.set(ATX_PIK_VERSION_LOCATION, 3).unwrap();
+
+        cache.commit(&mut ops).unwrap();
+
+        assert_eq!(ops.rollback_indices[&ATX_PIK_VERSION_LOCATION], 5);
+    }
 }